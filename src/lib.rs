@@ -67,6 +67,30 @@
 //! This crate does not depend on libstd, so it can be used in [`no_std`] projects.
 //!
 //! [`no_std`]: https://doc.rust-lang.org/book/first-edition/using-rust-without-the-standard-library.html
+//!
+//! # Cargo features
+//!
+//! The `strict_provenance` feature changes [`ByThinAddress`]'s comparison, ordering, and hashing
+//! impls to go through [`<*const T>::addr`][addr], so that no pointer-to-integer "exposing" cast
+//! is performed under the strict-provenance model now landing in `core`.  This makes the crate
+//! friendlier to Miri and other provenance-checked environments.  It is off by default.  When
+//! `ptr_meta` is also enabled, the `ptr_meta` impls below are used instead and this feature has
+//! no effect.  [`ByAddress`] is unaffected: its default `eq`/`cmp`/`hash` impls
+//! already compare the raw fat pointer directly, with no integer cast to avoid.
+//!
+//! The `ptr_meta` feature changes [`ByAddress`] to compare, order, and hash fat pointers by
+//! splitting them into their data address and their [`Pointee::Metadata`][Pointee] (via
+//! [`<*const T>::to_raw_parts`][to_raw_parts]) and handling each part explicitly, rather than
+//! comparing the fat pointer as a whole.  It also changes [`ByThinAddress`] to obtain its thin
+//! pointer from `to_raw_parts` instead of an `as *const ()` cast.  It is off by default because
+//! the underlying `ptr_metadata` API is not yet stable, and enabling it requires building this
+//! crate with a nightly toolchain.
+//!
+//! Both features must be declared in this crate's `[features]` table in `Cargo.toml`.
+//!
+//! [addr]: https://doc.rust-lang.org/std/primitive.pointer.html#method.addr
+//! [Pointee]: https://doc.rust-lang.org/core/ptr/trait.Pointee.html
+//! [to_raw_parts]: https://doc.rust-lang.org/std/primitive.pointer.html#method.to_raw_parts
 
 // Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
 // http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
@@ -75,6 +99,7 @@
 // except according to those terms.
 
 #![no_std]
+#![cfg_attr(feature = "ptr_meta", feature(ptr_metadata))]
 
 use core::cmp::Ordering;
 use core::convert::AsRef;
@@ -104,10 +129,23 @@ where
     T: ?Sized + Deref,
 {
     /// Convenience method for pointer casts.
-    fn addr(&self) -> *const T::Target {
+    fn as_ptr(&self) -> *const T::Target {
         &*self.0
     }
 
+    /// Returns the data address of the wrapped pointer, as used for comparison, ordering, and
+    /// hashing.
+    ///
+    /// For fat pointers (for example `&dyn Trait` or `&[T]`), this is only the address of the
+    /// pointer's data, not its attached metadata (vtable pointer or slice length).  For `Sized`
+    /// targets, [`Ord`] produces a total order consistent with this `usize`.  For fat pointers,
+    /// this `usize` is only the primary sort key: [`Ord`] additionally breaks ties using the
+    /// fat pointer's metadata, so two values with equal `addr()` but different metadata (for
+    /// example slices with the same start but different lengths) are not necessarily equal.
+    pub fn addr(&self) -> usize {
+        self.as_ptr().addr()
+    }
+
     /// Convert `&T` to `&ByAddress<T>`.
     pub fn from_ref(r: &T) -> &Self {
         // SAFETY: `struct ByAddress` is `repr(transparent)`.
@@ -117,6 +155,18 @@ where
     }
 }
 
+#[cfg(feature = "ptr_meta")]
+impl<T> ByAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    /// Split the wrapped pointer into its data address and its fat-pointer metadata (if any).
+    fn raw_parts(&self) -> (*const (), <T::Target as ptr::Pointee>::Metadata) {
+        self.as_ptr().to_raw_parts()
+    }
+}
+
 struct DebugAdapter<'a, T>(&'a T)
 where
     T: ?Sized + Deref + Debug;
@@ -154,43 +204,103 @@ where
 }
 
 /// Raw pointer equality
+#[cfg(not(feature = "ptr_meta"))]
 impl<T> PartialEq for ByAddress<T>
 where
     T: ?Sized + Deref,
 {
     fn eq(&self, other: &Self) -> bool {
-        ptr::eq(self.addr(), other.addr())
+        ptr::eq(self.as_ptr(), other.as_ptr())
+    }
+}
+
+/// Data address and metadata equality
+#[cfg(feature = "ptr_meta")]
+impl<T> PartialEq for ByAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let (data, meta) = self.raw_parts();
+        let (other_data, other_meta) = other.raw_parts();
+        data == other_data && meta == other_meta
     }
 }
 impl<T> Eq for ByAddress<T> where T: ?Sized + Deref {}
 
 /// Raw pointer ordering
+#[cfg(not(feature = "ptr_meta"))]
 impl<T> Ord for ByAddress<T>
 where
     T: ?Sized + Deref,
 {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.addr().cmp(&other.addr())
+        self.as_ptr().cmp(&other.as_ptr())
+    }
+}
+
+/// Data address and metadata ordering
+#[cfg(feature = "ptr_meta")]
+impl<T> Ord for ByAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (data, meta) = self.raw_parts();
+        let (other_data, other_meta) = other.raw_parts();
+        data.cmp(&other_data).then_with(|| meta.cmp(&other_meta))
     }
 }
 
 /// Raw pointer comparison
+#[cfg(not(feature = "ptr_meta"))]
 impl<T> PartialOrd for ByAddress<T>
 where
     T: ?Sized + Deref,
 {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.addr().cmp(&other.addr()))
+        Some(self.as_ptr().cmp(&other.as_ptr()))
+    }
+}
+
+/// Data address and metadata comparison
+#[cfg(feature = "ptr_meta")]
+impl<T> PartialOrd for ByAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (data, meta) = self.raw_parts();
+        let (other_data, other_meta) = other.raw_parts();
+        Some(data.cmp(&other_data).then_with(|| meta.cmp(&other_meta)))
     }
 }
 
 /// Raw pointer hashing
+#[cfg(not(feature = "ptr_meta"))]
+impl<T> Hash for ByAddress<T>
+where
+    T: ?Sized + Deref,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_ptr().hash(state)
+    }
+}
+
+/// Data address and metadata hashing
+#[cfg(feature = "ptr_meta")]
 impl<T> Hash for ByAddress<T>
 where
     T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
 {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.addr().hash(state)
+        let (data, meta) = self.raw_parts();
+        data.hash(state);
+        meta.hash(state);
     }
 }
 
@@ -262,10 +372,27 @@ where
     T: ?Sized + Deref,
 {
     /// Convenience method for pointer casts.
-    fn addr(&self) -> *const T::Target {
+    fn as_ptr(&self) -> *const T::Target {
         &*self.0
     }
 
+    /// The data address of the wrapped pointer, as a `usize`, obtained without exposing its
+    /// provenance.
+    #[cfg(all(feature = "strict_provenance", not(feature = "ptr_meta")))]
+    fn addr_usize(&self) -> usize {
+        self.as_ptr().cast::<()>().addr()
+    }
+
+    /// Returns the data address of the wrapped pointer, as used for comparison, ordering, and
+    /// hashing.
+    ///
+    /// Unlike [`ByAddress::addr`], this always returns only the data-pointer address, even if
+    /// `T::Target` is a fat pointer type such as `dyn Trait` or `[T]`.  [`Ord`] produces a total
+    /// order consistent with this `usize`.
+    pub fn addr(&self) -> usize {
+        self.as_ptr().addr()
+    }
+
     /// Convert `&T` to `&ByThinAddress<T>`.
     pub fn from_ref(r: &T) -> &Self {
         // SAFETY: `struct ByAddress` is `repr(transparent)`.
@@ -275,6 +402,18 @@ where
     }
 }
 
+#[cfg(feature = "ptr_meta")]
+impl<T> ByThinAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    /// The thin data pointer, with any fat-pointer metadata discarded.
+    fn thin_ptr(&self) -> *const () {
+        self.as_ptr().to_raw_parts().0
+    }
+}
+
 impl<T> Debug for ByThinAddress<T>
 where
     T: ?Sized + Deref + Debug,
@@ -296,43 +435,119 @@ where
 }
 
 /// Raw pointer equality
+#[cfg(not(feature = "ptr_meta"))]
+impl<T> PartialEq for ByThinAddress<T>
+where
+    T: ?Sized + Deref,
+{
+    #[cfg(not(feature = "strict_provenance"))]
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self.as_ptr() as *const (), other.as_ptr() as *const _)
+    }
+
+    #[cfg(feature = "strict_provenance")]
+    fn eq(&self, other: &Self) -> bool {
+        self.addr_usize() == other.addr_usize()
+    }
+}
+
+/// Thin pointer equality
+#[cfg(feature = "ptr_meta")]
 impl<T> PartialEq for ByThinAddress<T>
 where
     T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
 {
     fn eq(&self, other: &Self) -> bool {
-        core::ptr::eq(self.addr() as *const (), other.addr() as *const _)
+        self.thin_ptr() == other.thin_ptr()
     }
 }
 impl<T> Eq for ByThinAddress<T> where T: ?Sized + Deref {}
 
 /// Raw pointer ordering
+#[cfg(not(feature = "ptr_meta"))]
 impl<T> Ord for ByThinAddress<T>
 where
     T: ?Sized + Deref,
 {
+    #[cfg(not(feature = "strict_provenance"))]
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.as_ptr() as *const ()).cmp(&(other.as_ptr() as *const ()))
+    }
+
+    #[cfg(feature = "strict_provenance")]
     fn cmp(&self, other: &Self) -> Ordering {
-        (self.addr() as *const ()).cmp(&(other.addr() as *const ()))
+        self.addr_usize().cmp(&other.addr_usize())
+    }
+}
+
+/// Thin pointer ordering
+#[cfg(feature = "ptr_meta")]
+impl<T> Ord for ByThinAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.thin_ptr().cmp(&other.thin_ptr())
     }
 }
 
 /// Raw pointer comparison
+#[cfg(not(feature = "ptr_meta"))]
 impl<T> PartialOrd for ByThinAddress<T>
 where
     T: ?Sized + Deref,
 {
+    #[cfg(not(feature = "strict_provenance"))]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some((self.as_ptr() as *const ()).cmp(&(other.as_ptr() as *const ())))
+    }
+
+    #[cfg(feature = "strict_provenance")]
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some((self.addr() as *const ()).cmp(&(other.addr() as *const ())))
+        Some(self.addr_usize().cmp(&other.addr_usize()))
+    }
+}
+
+/// Thin pointer comparison
+#[cfg(feature = "ptr_meta")]
+impl<T> PartialOrd for ByThinAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.thin_ptr().cmp(&other.thin_ptr()))
     }
 }
 
 /// Raw pointer hashing
+#[cfg(not(feature = "ptr_meta"))]
 impl<T> Hash for ByThinAddress<T>
 where
     T: ?Sized + Deref,
 {
+    #[cfg(not(feature = "strict_provenance"))]
     fn hash<H: Hasher>(&self, state: &mut H) {
-        (self.addr() as *const ()).hash(state)
+        (self.as_ptr() as *const ()).hash(state)
+    }
+
+    #[cfg(feature = "strict_provenance")]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr_usize().hash(state)
+    }
+}
+
+/// Thin pointer hashing
+#[cfg(feature = "ptr_meta")]
+impl<T> Hash for ByThinAddress<T>
+where
+    T: ?Sized + Deref,
+    T::Target: ptr::Pointee,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.thin_ptr().hash(state)
     }
 }
 
@@ -414,6 +629,39 @@ mod tests {
         assert_eq!(a, b);
     }
 
+    #[cfg(feature = "ptr_meta")]
+    #[test]
+    fn test_ptr_meta_vtable_metadata() {
+        // Under `ptr_meta`, `ByAddress` compares fat pointers via `DynMetadata`'s `PartialEq`,
+        // which gives a well-defined answer instead of the whole-pointer comparison it replaces.
+        let t = Test {};
+        let tr1: &dyn A = &t;
+        let tr2: &dyn A = force_vtable(&t);
+
+        let a = ByAddress(tr1);
+        let b = ByAddress(tr2);
+
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_addr() {
+        let v = [1, 2, 3, 4];
+
+        assert_eq!(ByAddress(&v[..]).addr(), v.as_ptr() as usize);
+    }
+
+    #[test]
+    fn test_thin_addr_discards_metadata() {
+        let v = [1, 2, 3, 4];
+
+        let a = ByThinAddress(&v[0..4]);
+        let b = ByThinAddress(&v[0..2]);
+
+        assert_eq!(a.addr(), b.addr());
+        assert_eq!(a, b);
+    }
+
     #[test]
     fn test_debug() {
         let x = &1;